@@ -1,25 +1,290 @@
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
+use std::time::Duration;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum GatewayError {
     #[error("Missing variable {0}")]
-    MissingVariable(String),
+    MissingVariable(String, #[cfg(feature = "backtrace")] Backtrace),
     #[error(transparent)]
-    StdIOError(#[from] std::io::Error),
+    StdIOError(#[from] #[cfg_attr(feature = "backtrace", backtrace)] std::io::Error),
     #[error(transparent)]
-    ParseError(#[from] serde_json::Error),
+    ParseError(#[from] #[cfg_attr(feature = "backtrace", backtrace)] serde_json::Error),
     #[error("Error decoding argument: {0}")]
-    DecodeError(#[from] base64::DecodeError),
+    DecodeError(#[from] #[cfg_attr(feature = "backtrace", backtrace)] base64::DecodeError),
     #[error("Custom Error: {0}")]
-    CustomError(String),
+    CustomError(String, #[cfg(feature = "backtrace")] Backtrace),
     #[error("Function get is not implemented")]
     FunctionGetNotImplemented,
     #[error(transparent)]
-    ModelError(#[from] crate::model::error::ModelError),
+    ModelError(#[from] #[cfg_attr(feature = "backtrace", backtrace)] crate::model::error::ModelError),
     #[error("Tool call id not found in request")]
     ToolCallIdNotFound,
     #[error(transparent)]
-    ReqwestError(#[from] reqwest::Error),
+    ReqwestError(#[from] #[cfg_attr(feature = "backtrace", backtrace)] reqwest::Error),
     #[error(transparent)]
-    BoxedError(#[from] Box<dyn std::error::Error + Send + Sync>),
+    BoxedError(#[from] #[cfg_attr(feature = "backtrace", backtrace)] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Rate limited by {provider}")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        provider: String,
+    },
+    #[error("Context length exceeded: requested {requested} tokens, limit is {limit}")]
+    ContextLengthExceeded { limit: u32, requested: u32 },
+    #[error("Invalid API key")]
+    InvalidApiKey,
+    #[error("Provider unavailable (status {status}): {message}")]
+    ProviderUnavailable {
+        status: u16,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl GatewayError {
+    /// Construct a [`GatewayError::MissingVariable`], capturing a backtrace
+    /// when the `backtrace` feature is enabled.
+    pub fn missing_variable(name: impl Into<String>) -> Self {
+        GatewayError::MissingVariable(
+            name.into(),
+            #[cfg(feature = "backtrace")]
+            Backtrace::capture(),
+        )
+    }
+
+    /// Construct a [`GatewayError::CustomError`], capturing a backtrace when
+    /// the `backtrace` feature is enabled.
+    pub fn custom(message: impl Into<String>) -> Self {
+        GatewayError::CustomError(
+            message.into(),
+            #[cfg(feature = "backtrace")]
+            Backtrace::capture(),
+        )
+    }
+
+    /// Normalize an upstream provider's HTTP error response into a typed
+    /// variant.
+    ///
+    /// `provider` names the upstream (e.g. `"openai"`) and is carried verbatim
+    /// on [`GatewayError::RateLimited`]. `retry_after` is the raw `Retry-After`
+    /// response header, parsed here at construction time — the only point the
+    /// header is still available — so [`GatewayError::retry_after`] can return
+    /// it later. Branching is on the provider's JSON `error.code`/`error.type`
+    /// fields (the shape shared by OpenAI-compatible providers), falling back
+    /// to the HTTP `status`; anything unrecognized is preserved as a
+    /// `CustomError` carrying the upstream message.
+    pub fn from_provider_response(
+        provider: &str,
+        status: u16,
+        retry_after: Option<&str>,
+        body: &serde_json::Value,
+    ) -> Self {
+        let error = body.get("error");
+        let code = error
+            .and_then(|e| e.get("code"))
+            .and_then(|c| c.as_str())
+            .unwrap_or_default();
+        let kind = error
+            .and_then(|e| e.get("type"))
+            .and_then(|t| t.as_str())
+            .unwrap_or_default();
+        let message = error
+            .and_then(|e| e.get("message"))
+            .and_then(|m| m.as_str())
+            .unwrap_or("upstream provider error")
+            .to_string();
+        let retry_after = parse_retry_after(retry_after);
+
+        if status == 429 || code == "rate_limit_exceeded" || kind == "rate_limit_error" {
+            return GatewayError::RateLimited {
+                retry_after,
+                provider: provider.to_string(),
+            };
+        }
+        if code == "context_length_exceeded" || kind == "context_length_exceeded" {
+            // OpenAI-compatible providers don't emit the counts as structured
+            // fields — they're embedded in the message — so this is best-effort
+            // and defaults both to `0` when they can't be recovered.
+            let (limit, requested) = parse_context_lengths(&message);
+            return GatewayError::ContextLengthExceeded { limit, requested };
+        }
+        if status == 401 || code == "invalid_api_key" || kind == "authentication_error" {
+            return GatewayError::InvalidApiKey;
+        }
+        if matches!(status, 500 | 502 | 503 | 504) {
+            return GatewayError::ProviderUnavailable {
+                status,
+                message,
+                retry_after,
+            };
+        }
+        Self::custom(message)
+    }
+}
+
+/// Parse a `Retry-After` header into a [`Duration`]. Only the `delta-seconds`
+/// form is understood; the HTTP-date form is ignored (returns `None`).
+fn parse_retry_after(header: Option<&str>) -> Option<Duration> {
+    header?.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Best-effort extraction of the `(limit, requested)` token counts from a
+/// context-length error message. OpenAI's phrasing lists the model limit
+/// before the requested amount, so the first two integers are taken in that
+/// order; missing numbers default to `0`.
+fn parse_context_lengths(message: &str) -> (u32, u32) {
+    let mut nums = message
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|t| t.parse::<u32>().ok());
+    (nums.next().unwrap_or(0), nums.next().unwrap_or(0))
+}
+
+impl GatewayError {
+    /// HTTP status code this error should surface as.
+    ///
+    /// Client-caused failures map to `4xx`, unimplemented paths to `501`,
+    /// upstream transport failures to `502`, `ProviderUnavailable` to the
+    /// upstream status it carries, and everything else to `500`.
+    /// `ModelError` always maps to `502`; any status carried by the model
+    /// layer's error is not looked at.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            GatewayError::MissingVariable(..)
+            | GatewayError::DecodeError(_)
+            | GatewayError::ToolCallIdNotFound
+            | GatewayError::ContextLengthExceeded { .. } => 400,
+            GatewayError::FunctionGetNotImplemented => 501,
+            GatewayError::ReqwestError(_)
+            | GatewayError::BoxedError(_)
+            | GatewayError::ModelError(_) => 502,
+            GatewayError::RateLimited { .. } => 429,
+            GatewayError::InvalidApiKey => 401,
+            GatewayError::ProviderUnavailable { status, .. } => *status,
+            GatewayError::StdIOError(_)
+            | GatewayError::ParseError(_)
+            | GatewayError::CustomError(..) => 500,
+        }
+    }
+
+    /// OpenAI-style error `type` for the response envelope.
+    fn error_type(&self) -> &'static str {
+        match self.status_code() {
+            400 => "invalid_request_error",
+            401 => "authentication_error",
+            403 => "permission_error",
+            404 => "not_found_error",
+            429 => "rate_limit_error",
+            _ => "server_error",
+        }
+    }
+
+    /// Machine-readable error code identifying the failing variant.
+    fn error_code(&self) -> &'static str {
+        match self {
+            GatewayError::MissingVariable(..) => "missing_variable",
+            GatewayError::StdIOError(_) => "io_error",
+            GatewayError::ParseError(_) => "parse_error",
+            GatewayError::DecodeError(_) => "decode_error",
+            GatewayError::CustomError(..) => "custom_error",
+            GatewayError::FunctionGetNotImplemented => "function_get_not_implemented",
+            GatewayError::ModelError(_) => "model_error",
+            GatewayError::ToolCallIdNotFound => "tool_call_id_not_found",
+            GatewayError::ReqwestError(_) => "upstream_request_error",
+            GatewayError::BoxedError(_) => "internal_error",
+            GatewayError::RateLimited { .. } => "rate_limited",
+            GatewayError::ContextLengthExceeded { .. } => "context_length_exceeded",
+            GatewayError::InvalidApiKey => "invalid_api_key",
+            GatewayError::ProviderUnavailable { .. } => "provider_unavailable",
+        }
+    }
+
+    /// Whether retrying the originating request could plausibly succeed.
+    ///
+    /// Transient transport failures (timeouts, connection errors) and the
+    /// retryable upstream statuses `429`/`502`/`503`/`504` are retryable;
+    /// parse/decode/missing-variable/tool-call failures are permanent and
+    /// must not be retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GatewayError::ReqwestError(e) => {
+                if e.is_timeout() || e.is_connect() {
+                    return true;
+                }
+                matches!(e.status().map(|s| s.as_u16()), Some(429 | 502 | 503 | 504))
+            }
+            GatewayError::RateLimited { .. } => true,
+            GatewayError::ProviderUnavailable { status, .. } => {
+                matches!(status, 502 | 503 | 504)
+            }
+            // `ParseError`/`DecodeError`/`MissingVariable`/`ToolCallIdNotFound`
+            // and every other variant are permanent.
+            _ => false,
+        }
+    }
+
+    /// Delay requested by the upstream before retrying, if known.
+    ///
+    /// The `Retry-After` header is parsed at construction time (see
+    /// [`GatewayError::from_provider_response`]) and carried on the
+    /// `RateLimited`/`ProviderUnavailable` variants. A bare `ReqwestError`
+    /// keeps no headers, so transport-level failures return `None` and callers
+    /// fall back to their own backoff schedule.
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            GatewayError::RateLimited { retry_after, .. }
+            | GatewayError::ProviderUnavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Error body following the OpenAI `{ "error": { message, type, code } }`
+    /// shape so existing SDK clients can parse gateway failures directly.
+    pub fn error_body(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "message": self.to_string(),
+                "type": self.error_type(),
+                "code": self.error_code(),
+            }
+        })
+    }
+
+    /// Emit this error through `tracing` at the response boundary with a
+    /// per-variant severity: `warn!` for client-caused failures and `error!`
+    /// for upstream or internal ones. The variant code, resolved HTTP status
+    /// and any embedded provider are attached as structured fields so error
+    /// rates stay queryable in log pipelines.
+    pub fn emit_trace(&self) {
+        let code = self.error_code();
+        let status = self.status_code();
+        let provider = match self {
+            GatewayError::RateLimited { provider, .. } => Some(provider.as_str()),
+            _ => None,
+        };
+        let client_caused = matches!(
+            self,
+            GatewayError::MissingVariable(..)
+                | GatewayError::DecodeError(_)
+                | GatewayError::ToolCallIdNotFound
+                | GatewayError::ContextLengthExceeded { .. }
+                | GatewayError::InvalidApiKey
+        );
+        if client_caused {
+            tracing::warn!(error.code = code, http.status = status, error.provider = ?provider, "{self}");
+        } else {
+            tracing::error!(error.code = code, http.status = status, error.provider = ?provider, "{self}");
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for GatewayError {
+    fn into_response(self) -> axum::response::Response {
+        self.emit_trace();
+        let status = axum::http::StatusCode::from_u16(self.status_code())
+            .unwrap_or(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        (status, axum::Json(self.error_body())).into_response()
+    }
 }